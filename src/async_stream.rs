@@ -34,57 +34,148 @@
 //!
 //! On success the async closure should return `Ok(())`.
 //!
+//! Items sent with `send` are buffered in a bounded queue, so the
+//! producing closure can hand off several items per poll instead of
+//! being forced to yield back to the executor after every single one.
+//! `send` only returns a pending future once that queue is full.
+//!
 //! [async]: https://rust-lang.github.io/async-book/getting_started/async_await_primer.html
 //! [send]: async_stream/struct.Sender.html#method.send
 //!
-use std::cell::Cell;
+use std::cell::RefCell;
 use std::future::Future;
+use std::io;
 use std::marker::PhantomData;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::task::{Context, Poll};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 
+use bytes::Buf;
+use futures::io::{AsyncBufRead, AsyncRead};
 use futures::Stream;
 
-/// Future returned by the Sender.send() method.
-///
-/// Completes when the item is sent.
-#[must_use]
-pub struct SenderFuture {
-    is_ready:   bool,
+/// Default capacity of the bounded queue used by [`AsyncStream::new`].
+const DEFAULT_CAPACITY: usize = 16;
+
+// A tiny fixed-capacity SPSC ring buffer. It's only ever touched from
+// within the single `poll_next` call that drives both the consumer
+// (AsyncStream) and, indirectly, the producer (the closure's future),
+// so there's never any real concurrent access - no atomics needed.
+struct Ring<I> {
+    buf:  Vec<Option<I>>,
+    head: usize,
+    len:  usize,
 }
 
-impl SenderFuture {
-    fn new() -> SenderFuture {
-        SenderFuture {
-            is_ready:   false,
+impl<I> Ring<I> {
+    fn new(capacity: usize) -> Ring<I> {
+        let capacity = capacity.max(1);
+        let mut buf = Vec::with_capacity(capacity);
+        buf.resize_with(capacity, || None);
+        Ring {
+            buf,
+            head: 0,
+            len: 0,
         }
     }
-}
 
-impl Future for SenderFuture {
-    type Output = ();
+    fn is_full(&self) -> bool {
+        self.len == self.buf.len()
+    }
 
-    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self.is_ready {
-            Poll::Ready(())
-        } else {
-            self.is_ready = true;
-            Poll::Pending
+    // Push an item, returning it back if the ring is full.
+    fn push(&mut self, item: I) -> Result<(), I> {
+        if self.is_full() {
+            return Err(item);
         }
+        let idx = (self.head + self.len) % self.buf.len();
+        self.buf[idx] = Some(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<I> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.buf[self.head].take();
+        self.head = (self.head + 1) % self.buf.len();
+        self.len -= 1;
+        item
     }
 }
 
 // Only internally used by one AsyncStream and never shared
 // in any other way, so we don't have to use Arc<Mutex<..>>.
+struct Shared<I> {
+    ring:       RefCell<Ring<I>>,
+    // Waker of a `send` that found the ring full, woken up once
+    // `poll_next` frees a slot by popping an item.
+    send_waker: RefCell<Option<Waker>>,
+}
+
+// The RefCells are never actually accessed from more than one thread
+// at the same time (see the comment above), so - just like Sender,
+// which wraps this same Arc<Shared<I>> - it's sound to hand this
+// across an await point/thread boundary.
+unsafe impl<I> Send for Shared<I> {}
+unsafe impl<I> Sync for Shared<I> {}
+
+/// Future returned by the Sender.send() method.
+///
+/// Completes when the item has been queued, which might require
+/// waiting for the consumer to free up space in the queue.
+#[must_use]
+pub struct SenderFuture<I> {
+    shared: Arc<Shared<I>>,
+    item:   Option<I>,
+}
+
+// See the comment on `Shared`: items are never actually accessed
+// from more than one thread at the same time.
+unsafe impl<I> Send for SenderFuture<I> {}
+// SenderFuture never hands out a reference into itself, so it's fine
+// to treat it as movable regardless of whether `I` is `Unpin`.
+impl<I> Unpin for SenderFuture<I> {}
+
+impl<I> Future for SenderFuture<I> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let item = match this.item.take() {
+            Some(item) => item,
+            // The item was already queued by an earlier poll, which
+            // returned Ready then; polling again after completion just
+            // reports Ready again.
+            None => return Poll::Ready(()),
+        };
+        match this.shared.ring.borrow_mut().push(item) {
+            Ok(()) => Poll::Ready(()),
+            Err(item) => {
+                this.item = Some(item);
+                *this.shared.send_waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
 /// Type of the sender passed as first argument into the async closure.
-pub struct Sender<I, E>(Arc<Cell<Option<I>>>, PhantomData<E>);
+pub struct Sender<I, E>(Arc<Shared<I>>, PhantomData<E>);
 unsafe impl<I, E> Sync for Sender<I, E> {}
 unsafe impl<I, E> Send for Sender<I, E> {}
 
 impl<I, E> Sender<I, E> {
-    fn new(item_opt: Option<I>) -> Sender<I, E> {
-        Sender(Arc::new(Cell::new(item_opt)), PhantomData::<E>)
+    fn new(capacity: usize) -> Sender<I, E> {
+        Sender(
+            Arc::new(Shared {
+                ring:       RefCell::new(Ring::new(capacity)),
+                send_waker: RefCell::new(None),
+            }),
+            PhantomData::<E>,
+        )
     }
 
     // note that this is NOT impl Clone for Sender, it's private.
@@ -93,10 +184,71 @@ impl<I, E> Sender<I, E> {
     }
 
     /// Send one item to the stream.
-    pub fn send<T>(&mut self, item: T) -> SenderFuture
+    ///
+    /// If the internal queue is full, the returned future will not
+    /// resolve until the consumer has made room for it.
+    pub fn send<T>(&mut self, item: T) -> SenderFuture<I>
     where T: Into<I> {
-        self.0.set(Some(item.into()));
-        SenderFuture::new()
+        SenderFuture {
+            shared: self.0.clone(),
+            item:   Some(item.into()),
+        }
+    }
+}
+
+// Shared between an AsyncStream and the AbortHandle(s) obtained from
+// it. Unlike Shared<Item>, this one really is accessed from more than
+// one task/thread at a time - abort() can be called from anywhere -
+// so it uses real synchronization primitives.
+struct AbortInner {
+    aborted: AtomicBool,
+    waker:   Mutex<Option<Waker>>,
+}
+
+impl AbortInner {
+    fn new() -> AbortInner {
+        AbortInner {
+            aborted: AtomicBool::new(false),
+            waker:   Mutex::new(None),
+        }
+    }
+}
+
+/// A handle that can cancel the [`AsyncStream`][AsyncStream] it was
+/// obtained from, e.g. because a client disconnected mid-transfer.
+///
+/// [AsyncStream]: struct.AsyncStream.html
+#[derive(Clone)]
+pub struct AbortHandle(Arc<AbortInner>);
+
+impl AbortHandle {
+    /// Cancel the stream.
+    ///
+    /// The next time the stream is polled it drops the future that was
+    /// driving it (and any resources, like open files or sockets, that
+    /// future was holding). If the stream is currently being polled by
+    /// another task, that task is woken up immediately.
+    ///
+    /// Items the closure already pushed into the queue before the
+    /// abort are not discarded - they're still delivered, in order,
+    /// before the stream ends. Once the queue is drained the stream
+    /// yields the error set with
+    /// [`with_cancel_error`][AsyncStream::with_cancel_error], or just
+    /// ends if none was set.
+    ///
+    /// [AsyncStream::with_cancel_error]: struct.AsyncStream.html#method.with_cancel_error
+    pub fn abort(&self) {
+        self.0.aborted.store(true, Ordering::SeqCst);
+        if let Some(waker) = self.0.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+
+    /// Returns true if [`abort`][AbortHandle::abort] has been called.
+    ///
+    /// [AbortHandle::abort]: struct.AbortHandle.html#method.abort
+    pub fn is_aborted(&self) -> bool {
+        self.0.aborted.load(Ordering::SeqCst)
     }
 }
 
@@ -111,8 +263,12 @@ impl<I, E> Sender<I, E> {
 /// [Stream]: https://docs.rs/futures/0.3/futures/stream/trait.Stream.html
 #[must_use]
 pub struct AsyncStream<Item, Error> {
-    item: Sender<Item, Error>,
-    fut:  Option<Pin<Box<dyn Future<Output = Result<(), Error>> + 'static + Send>>>,
+    shared:         Arc<Shared<Item>>,
+    error:          Option<Error>,
+    fut:            Option<Pin<Box<dyn Future<Output = Result<(), Error>> + 'static + Send>>>,
+    content_length: Option<u64>,
+    abort:          Arc<AbortInner>,
+    cancel_error:   Option<Error>,
 }
 
 impl<Item, Error: 'static + Send> AsyncStream<Item, Error> {
@@ -124,18 +280,90 @@ impl<Item, Error: 'static + Send> AsyncStream<Item, Error> {
     ///
     /// The AsyncStream instance that is returned impl's both
     /// a futures 0.1 Stream and a futures 0.3 Stream.
+    ///
+    /// Items sent by the closure are buffered in a queue with a
+    /// default capacity; see [`new_with_capacity`][new_with_capacity]
+    /// to configure it.
+    ///
+    /// [new_with_capacity]: struct.AsyncStream.html#method.new_with_capacity
     pub fn new<F, R>(f: F) -> Self
     where
         F: FnOnce(Sender<Item, Error>) -> R,
         R: Future<Output = Result<(), Error>> + Send + 'static,
         Item: 'static,
     {
-        let sender = Sender::new(None);
+        AsyncStream::new_with_capacity(DEFAULT_CAPACITY, f)
+    }
+
+    /// Like [`new`][new], but with a configurable capacity for the
+    /// internal queue that buffers items sent by the closure.
+    ///
+    /// A larger capacity lets the closure batch up more items before
+    /// it has to wait for the consumer to poll the stream again.
+    ///
+    /// [new]: struct.AsyncStream.html#method.new
+    pub fn new_with_capacity<F, R>(capacity: usize, f: F) -> Self
+    where
+        F: FnOnce(Sender<Item, Error>) -> R,
+        R: Future<Output = Result<(), Error>> + Send + 'static,
+        Item: 'static,
+    {
+        let sender = Sender::new(capacity);
+        let shared = sender.clone().0;
         AsyncStream::<Item, Error> {
-            item: sender.clone(),
-            fut:  Some(Box::pin(f(sender))),
+            shared,
+            error: None,
+            fut: Some(Box::pin(f(sender))),
+            content_length: None,
+            abort: Arc::new(AbortInner::new()),
+            cancel_error: None,
         }
     }
+
+    /// Record the total number of bytes the stream will produce.
+    ///
+    /// This is used by the [`http_body::Body`][Body] implementation to
+    /// report an exact [`size_hint`][size_hint], which lets hyper send
+    /// a `Content-Length` header instead of switching to chunked
+    /// transfer encoding.
+    ///
+    /// [Body]: https://docs.rs/http-body/0.4/http_body/trait.Body.html
+    /// [size_hint]: #method.size_hint
+    pub fn with_content_length(mut self, content_length: u64) -> Self {
+        self.content_length = Some(content_length);
+        self
+    }
+
+    /// Set the error the stream will yield if it gets cancelled
+    /// through an [`AbortHandle`][AbortHandle] before finishing on
+    /// its own.
+    ///
+    /// Without this, an aborted stream just ends (as if the driving
+    /// future had returned `Ok(())`).
+    ///
+    /// [AbortHandle]: struct.AbortHandle.html
+    pub fn with_cancel_error(mut self, error: Error) -> Self {
+        self.cancel_error = Some(error);
+        self
+    }
+
+    /// Get a handle that can be used to cancel this stream from
+    /// elsewhere, e.g. when a client disconnects mid-transfer.
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle(self.abort.clone())
+    }
+}
+
+impl<Item, Error> AsyncStream<Item, Error> {
+    // Pop the next queued item, if any, and wake up a `send` that
+    // was waiting for room in the ring.
+    fn pop(&self) -> Option<Item> {
+        let item = self.shared.ring.borrow_mut().pop()?;
+        if let Some(waker) = self.shared.send_waker.borrow_mut().take() {
+            waker.wake();
+        }
+        Some(item)
+    }
 }
 
 /// Stream implementation for Futures 0.3.
@@ -143,58 +371,345 @@ impl<I, E: Unpin> Stream for AsyncStream<I, E> {
     type Item = Result<I, E>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<I, E>>> {
-        let pollres = {
-            let fut = self.fut.as_mut().unwrap();
-            fut.as_mut().poll(cx)
+        // Register our waker so that an AbortHandle::abort() called from
+        // another task can wake us up promptly instead of waiting for
+        // the next natural poll.
+        *self.abort.waker.lock().unwrap() = Some(cx.waker().clone());
+        // Only short-circuit while there's still a future to cut off.
+        // Once `fut` is gone the driving future already finished on its
+        // own and may have left a real error parked in `self.error` -
+        // that must still reach the consumer instead of being silently
+        // replaced by the (usually absent) cancel error.
+        if self.fut.is_some() && self.abort.aborted.load(Ordering::SeqCst) {
+            // Drop the driving future (and whatever resources, like open
+            // files or sockets, it was holding). Items it already pushed
+            // into the ring are real, produced output and must still be
+            // delivered - only once the ring is drained do we fall back
+            // to `self.error` below and report the cancel error, if any.
+            self.fut = None;
+            self.error = self.cancel_error.take();
+        }
+        // Items queued up by the producer are always drained first.
+        if let Some(item) = self.pop() {
+            return Poll::Ready(Some(Ok(item)));
+        }
+        let fut = match self.fut.as_mut() {
+            Some(fut) => fut,
+            // The driving future already finished, or was dropped by an
+            // abort; report the error it ended with (or the cancel error),
+            // if any, exactly once.
+            None => return Poll::Ready(self.error.take().map(Err)),
         };
-        match pollres {
-            // If the future returned Poll::Ready, that signals the end of the stream.
-            Poll::Ready(Ok(_)) => Poll::Ready(None),
-            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
-            Poll::Pending => {
-                // Pending means that some sub-future returned pending. That sub-future
-                // _might_ have been the SenderFuture returned by Sender.send, so
-                // check if there is an item available in self.item.
-                let mut item = self.item.0.replace(None);
-                if item.is_none() {
-                    Poll::Pending
-                } else {
-                    Poll::Ready(Some(Ok(item.take().unwrap())))
+        match fut.as_mut().poll(cx) {
+            Poll::Ready(Ok(())) => {
+                self.fut = None;
+                match self.pop() {
+                    Some(item) => Poll::Ready(Some(Ok(item))),
+                    None => Poll::Ready(None),
                 }
             },
+            Poll::Ready(Err(e)) => {
+                self.fut = None;
+                match self.pop() {
+                    Some(item) => {
+                        self.error = Some(e);
+                        Poll::Ready(Some(Ok(item)))
+                    },
+                    None => Poll::Ready(Some(Err(e))),
+                }
+            },
+            Poll::Pending => match self.pop() {
+                Some(item) => Poll::Ready(Some(Ok(item))),
+                None => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl<Item, Error> AsyncStream<Item, Error>
+where
+    Item: Buf + Unpin + 'static,
+    Error: Into<io::Error> + Unpin,
+{
+    /// Turn this stream into an [`AsyncRead`]/[`AsyncBufRead`] reader.
+    ///
+    /// [`AsyncRead`]: https://docs.rs/futures/0.3/futures/io/trait.AsyncRead.html
+    /// [`AsyncBufRead`]: https://docs.rs/futures/0.3/futures/io/trait.AsyncBufRead.html
+    pub fn into_async_read(self) -> AsyncStreamReader<Item, Error> {
+        AsyncStreamReader {
+            inner: self,
+            state: ReadState::new(),
+        }
+    }
+}
+
+// Tracks the chunk that's currently being read out byte-by-byte, so
+// that a caller's small `poll_read` buffer doesn't force us to throw
+// away the rest of a chunk pulled from the stream.
+struct ReadState<Item> {
+    buf: Option<Item>,
+}
+
+impl<Item: Buf> ReadState<Item> {
+    fn new() -> ReadState<Item> {
+        ReadState { buf: None }
+    }
+
+    fn has_remaining(&self) -> bool {
+        self.buf.as_ref().map(Buf::has_remaining).unwrap_or(false)
+    }
+}
+
+/// Reader returned by [`AsyncStream::into_async_read`][into_async_read].
+///
+/// [into_async_read]: struct.AsyncStream.html#method.into_async_read
+#[must_use]
+pub struct AsyncStreamReader<Item, Error> {
+    inner: AsyncStream<Item, Error>,
+    state: ReadState<Item>,
+}
+
+impl<Item, Error> AsyncBufRead for AsyncStreamReader<Item, Error>
+where
+    Item: Buf + Unpin + 'static,
+    Error: Into<io::Error> + Unpin,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        while !this.state.has_remaining() {
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => this.state.buf = Some(item),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e.into())),
+                Poll::Ready(None) => {
+                    this.state.buf = None;
+                    break;
+                },
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        match this.state.buf.as_ref() {
+            Some(item) => Poll::Ready(Ok(item.chunk())),
+            None => Poll::Ready(Ok(&[])),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        if let Some(item) = this.state.buf.as_mut() {
+            item.advance(amt);
         }
     }
 }
 
+impl<Item, Error> AsyncRead for AsyncStreamReader<Item, Error>
+where
+    Item: Buf + Unpin + 'static,
+    Error: Into<io::Error> + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let data = match self.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(Ok(data)) => data,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+        let n = std::cmp::min(data.len(), buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
 #[cfg(feature = "hyper")]
 mod hyper {
-    use bytes;
-    use futures01::Poll as Poll01;
-    use hyper;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use bytes::Buf;
+    use futures::Stream;
+    use http::HeaderMap;
+    use http_body::SizeHint;
+
+    use super::AsyncStream;
 
-    /// hyper::body::Payload trait implementation.
+    /// http_body::Body trait implementation.
     ///
-    /// This implementation allows you to use anything that implements
-    /// IntoBuf as a Payload item.
-    impl<Item, Error> hyper::body::Payload for AsyncStream<Item, Error>
+    /// This implementation allows an `AsyncStream` of buffers to be
+    /// used directly as a hyper request or response body.
+    impl<Item, Error> http_body::Body for AsyncStream<Item, Error>
     where
-        Item: bytes::buf::IntoBuf + Send + Sync + 'static,
-        Item::Buf: Send,
-        Error: std::error::Error + Send + Sync + 'static,
+        Item: Buf + Unpin + Send + 'static,
+        Error: std::error::Error + Send + Sync + Unpin + 'static,
     {
-        type Data = Item::Buf;
+        type Data = Item;
         type Error = Error;
 
-        fn poll_data(&mut self) -> Poll01<Option<Self::Data>, Self::Error> {
-            match self.poll() {
-                Ok(Async01::Ready(Some(item))) => Ok(Async01::Ready(Some(item.into_buf()))),
-                Ok(Async01::Ready(None)) => Ok(Async01::Ready(None)),
-                Ok(Async01::NotReady) => Ok(Async01::NotReady),
-                Err(e) => Err(e),
+        fn poll_data(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Stream::poll_next(self, cx)
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(None))
+        }
+
+        fn size_hint(&self) -> SizeHint {
+            match self.content_length {
+                Some(len) => SizeHint::with_exact(len),
+                None => SizeHint::default(),
             }
         }
     }
 }
 
-#[cfg(feature = "hyper")]
-use hyper::*;
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use futures::io::AsyncReadExt;
+    use futures::task::noop_waker;
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn is_send<T: Send>() {}
+
+    // Regression test: Shared<I> (reached through Arc<Shared<Item>>)
+    // must be Send/Sync so that AsyncStream itself stays Send - that's
+    // what lets a stream be moved into a hyper/tokio connection task
+    // across an `.await`.
+    #[test]
+    fn async_stream_is_send() {
+        is_send::<AsyncStream<u8, io::Error>>();
+    }
+
+    #[test]
+    fn send_future_pends_until_ring_has_room() {
+        let mut sender: Sender<u8, io::Error> = Sender::new(2);
+        let shared = sender.0.clone();
+
+        assert!(shared.ring.borrow_mut().push(0).is_ok());
+        assert!(shared.ring.borrow_mut().push(1).is_ok());
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        // The ring is already full, so a further send must pend and
+        // register its waker instead of overflowing the ring.
+        let mut fut = sender.send(2u8);
+        assert_eq!(Pin::new(&mut fut).poll(&mut cx), Poll::Pending);
+        assert!(shared.send_waker.borrow().is_some());
+
+        // Freeing a slot (as poll_next does when it pops an item) must
+        // let the pending send complete on its next poll.
+        assert_eq!(shared.ring.borrow_mut().pop(), Some(0));
+        assert_eq!(Pin::new(&mut fut).poll(&mut cx), Poll::Ready(()));
+
+        assert_eq!(shared.ring.borrow_mut().pop(), Some(1));
+        assert_eq!(shared.ring.borrow_mut().pop(), Some(2));
+    }
+
+    #[test]
+    fn stream_drains_in_order_with_a_small_capacity() {
+        let mut strm = AsyncStream::<u8, io::Error>::new_with_capacity(2, |mut tx| async move {
+            for i in 0u8..10 {
+                tx.send(i).await;
+            }
+            Ok(())
+        });
+        let collected: Vec<u8> = block_on(async {
+            let mut out = Vec::new();
+            while let Some(item) = strm.next().await {
+                out.push(item.unwrap());
+            }
+            out
+        });
+        assert_eq!(collected, (0u8..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn into_async_read_reads_across_chunk_boundaries() {
+        let strm = AsyncStream::<Bytes, io::Error>::new_with_capacity(2, |mut tx| async move {
+            tx.send(Bytes::from_static(b"hel")).await;
+            tx.send(Bytes::from_static(b"lo, ")).await;
+            tx.send(Bytes::from_static(b"world")).await;
+            Ok(())
+        });
+        let mut reader = strm.into_async_read();
+        let out = block_on(async {
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).await.unwrap();
+            out
+        });
+        assert_eq!(out, b"hello, world");
+    }
+
+    #[test]
+    fn abort_does_not_swallow_a_pending_error() {
+        let mut strm = AsyncStream::<u8, &'static str>::new_with_capacity(4, |mut tx| async move {
+            tx.send(1u8).await;
+            Err("boom")
+        })
+        .with_cancel_error("cancelled");
+        let handle = strm.abort_handle();
+
+        let (first, second) = block_on(async {
+            // This poll both yields the queued item and runs the
+            // driving future to completion, parking "boom" in
+            // self.error to be reported on the *next* poll.
+            let first = strm.next().await;
+            // Racing an abort() in after the error is parked, but
+            // before it's been handed to the consumer, must not
+            // replace it with the cancel error (or silently end the
+            // stream).
+            handle.abort();
+            let second = strm.next().await;
+            (first, second)
+        });
+        assert_eq!(first, Some(Ok(1)));
+        assert_eq!(second, Some(Err("boom")));
+    }
+
+    #[test]
+    fn abort_still_delivers_buffered_items_before_cancelling() {
+        let mut strm = AsyncStream::<u8, &'static str>::new_with_capacity(8, |mut tx| async move {
+            tx.send(1u8).await;
+            tx.send(2u8).await;
+            tx.send(3u8).await;
+            // Stall forever, as if waiting on a slow backend read.
+            std::future::pending::<()>().await;
+            Ok(())
+        })
+        .with_cancel_error("cancelled");
+        let handle = strm.abort_handle();
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        // Drive the closure up to the point where it stalls, which
+        // leaves 1, 2 and 3 buffered in the ring - and drains the
+        // first of them.
+        assert_eq!(Pin::new(&mut strm).poll_next(&mut cx), Poll::Ready(Some(Ok(1))));
+
+        handle.abort();
+
+        // The items already produced before the abort must still be
+        // delivered...
+        assert_eq!(Pin::new(&mut strm).poll_next(&mut cx), Poll::Ready(Some(Ok(2))));
+        assert_eq!(Pin::new(&mut strm).poll_next(&mut cx), Poll::Ready(Some(Ok(3))));
+        // ...and only once the ring is empty does the cancellation
+        // surface.
+        assert_eq!(
+            Pin::new(&mut strm).poll_next(&mut cx),
+            Poll::Ready(Some(Err("cancelled")))
+        );
+    }
+}